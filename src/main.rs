@@ -1,10 +1,18 @@
 use argh::FromArgs;
+use crossterm::cursor::MoveTo;
+use crossterm::event::{read as read_event, Event, KeyCode};
+use crossterm::execute;
+use crossterm::style::{Color as CtColor, Print, ResetColor, SetForegroundColor};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, size as terminal_size, Clear, ClearType,
+    EnterAlternateScreen, LeaveAlternateScreen,
+};
 use percent_encoding::percent_decode;
 use regex::{Matches, Regex};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{BufReader, Write};
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 use xml::attribute::OwnedAttribute;
 use xml::reader::{EventReader, XmlEvent};
@@ -71,13 +79,58 @@ fn is_end_element(event: &XmlEvent, element_name: &str) -> bool {
     }
 }
 
+// advance event_reader until a start element named element_name is
+// found, returning its attributes. Bails out with None on EndDocument
+// or a parse error instead of spinning forever: EventReader::next keeps
+// returning the same cached EndDocument/error on every call once the
+// stream ends, so a loop that doesn't check for it never terminates.
+fn seek_start_element<R: Read>(
+    event_reader: &mut EventReader<R>,
+    element_name: &str,
+) -> Option<Vec<OwnedAttribute>> {
+    loop {
+        let event = match event_reader.next() {
+            Ok(event) => event,
+            Err(_) => return None,
+        };
+
+        if let XmlEvent::EndDocument = event {
+            return None;
+        } else if let Some(attrs) = is_start_element(&event, element_name) {
+            return Some(attrs);
+        }
+    }
+}
+
+// when this is called, event_reader has already seen the start of the
+// element; collect character data up to its matching end tag
+fn read_element_text<R: Read>(
+    event_reader: &mut EventReader<R>,
+    element_name: &str,
+) -> Option<String> {
+    let mut text = String::new();
+    loop {
+        let event = match event_reader.next() {
+            Ok(event) => event,
+            Err(_) => return None,
+        };
+
+        if is_end_element(&event, element_name) {
+            break;
+        } else if let XmlEvent::Characters(s) = event {
+            text.push_str(&s);
+        }
+    }
+    Some(text)
+}
+
 // Find the name of the content file of an epub file
 fn get_content_file_name(epub: &mut ZipArchive<File>) -> EpubResult<String> {
     let container = epub
         .by_name("META-INF/container.xml")
         .map_err(EpubError::Zip)?;
 
-    for e in EventReader::new(BufReader::new(container)) {
+    for e in EventReader::new(container) {
         match e {
             Ok(event) => {
                 if let Some(attributes) = is_start_element(&event, "rootfile") {
@@ -94,9 +147,20 @@ fn get_content_file_name(epub: &mut ZipArchive<File>) -> EpubResult<String> {
     ))
 }
 
+// which grammar a table of contents file uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TocFormat {
+    // EPUB2-style NCX: <navMap>/<navPoint>
+    Ncx,
+    // EPUB3-style navigation document: <nav epub:type="toc"><ol><li><a>
+    Nav,
+}
+
 // find the name of the toc file, the name of the oebps folder,
 // and a list of the xhtml documents in the spine
-fn get_spine_documents(epub: &mut ZipArchive<File>) -> EpubResult<(String, Vec<String>)> {
+fn get_spine_documents(
+    epub: &mut ZipArchive<File>,
+) -> EpubResult<(String, TocFormat, Vec<String>)> {
     // oebps is the folder containing the content_file, necessary since
     // hrefs in the content file are relative to the content file
     let (content_file, oebps) = {
@@ -106,19 +170,18 @@ fn get_spine_documents(epub: &mut ZipArchive<File>) -> EpubResult<(String, Vec<S
 
         (content_file, oebps)
     };
-
     let mut content_parser = EventReader::new(content_file);
 
     // iterate to the start of the manifest section
-    while content_parser
-        .next()
-        .ok()
-        .and_then(|e| is_start_element(&e, "manifest"))
-        .is_none()
-    {}
+    if seek_start_element(&mut content_parser, "manifest").is_none() {
+        return Err(EpubError::Epub("Malformed Xml".to_string()));
+    }
 
-    // collect the ids for all the xhtml documents
+    // collect the ids for all the xhtml documents, and remember the href
+    // of the EPUB3 nav document (manifest item with properties="nav"),
+    // in case the spine has no NCX toc to fall back on
     let mut content_ids = HashMap::new();
+    let mut nav_href = None;
     loop {
         let event = match content_parser.next() {
             Ok(event) => event,
@@ -132,6 +195,12 @@ fn get_spine_documents(epub: &mut ZipArchive<File>) -> EpubResult<(String, Vec<S
             let id = get_attribute(&attrs, "id");
             let href = get_attribute(&attrs, "href");
 
+            if let Some(properties) = get_attribute(&attrs, "properties") {
+                if properties.split_whitespace().any(|p| p == "nav") {
+                    nav_href = href.clone();
+                }
+            }
+
             if let (Some(media_type), Some(id), Some(href)) = (media_type, id, href) {
                 if media_type == "application/xhtml+xml" || media_type == "application/x-dtbncx+xml"
                 {
@@ -141,8 +210,10 @@ fn get_spine_documents(epub: &mut ZipArchive<File>) -> EpubResult<(String, Vec<S
         }
     }
 
-    // iterate to the start of the spine, and find the id for the toc file
-    let toc_id = loop {
+    // iterate to the start of the spine, and find the id for the toc file,
+    // falling back to the EPUB3 nav document when the spine carries no
+    // toc attribute at all
+    let (toc_id, toc_format) = loop {
         let event = match content_parser.next() {
             Ok(event) => event,
             Err(_) => return Err(EpubError::Epub("Malformed Epub".to_string())),
@@ -150,15 +221,21 @@ fn get_spine_documents(epub: &mut ZipArchive<File>) -> EpubResult<(String, Vec<S
 
         if let Some(attrs) = is_start_element(&event, "spine") {
             match get_attribute(&attrs, "toc") {
-                Some(toc_id) => break toc_id,
-                None => return Err(EpubError::Epub("Malformed Epub".to_string())),
+                Some(toc_id) => break (Some(toc_id), TocFormat::Ncx),
+                None => break (None, TocFormat::Nav),
             }
         }
     };
 
-    let toc = match content_ids.get(&toc_id) {
-        Some(toc) => format!("{}{}", oebps, toc),
-        None => return Err(EpubError::Epub("Malformed Epub".to_string())),
+    let toc = match toc_format {
+        TocFormat::Ncx => match toc_id.as_ref().and_then(|id| content_ids.get(id)) {
+            Some(toc) => format!("{}{}", oebps, toc),
+            None => return Err(EpubError::Epub("Malformed Epub".to_string())),
+        },
+        TocFormat::Nav => match &nav_href {
+            Some(href) => format!("{}{}", oebps, href),
+            None => return Err(EpubError::Epub("Malformed Epub".to_string())),
+        },
     };
 
     // collect the spine documents
@@ -179,7 +256,130 @@ fn get_spine_documents(epub: &mut ZipArchive<File>) -> EpubResult<(String, Vec<S
         }
     }
 
-    Ok((toc, spine))
+    Ok((toc, toc_format, spine))
+}
+
+// a single dc:creator entry, along with its EPUB3 file-as sort name if
+// the OPF refines it with a <meta property="file-as"> element
+#[derive(Debug)]
+struct Author {
+    name: String,
+    file_as: Option<String>,
+}
+
+// metadata pulled from the OPF <metadata> block, reachable via the
+// rootfile located by get_content_file_name
+#[derive(Debug, Default)]
+struct BookMetadata {
+    title: Option<String>,
+    authors: Vec<Author>,
+    subjects: Vec<String>,
+    series: Option<String>,
+}
+
+impl BookMetadata {
+    fn title_or_unknown(&self) -> &str {
+        self.title.as_deref().unwrap_or("Unknown Title")
+    }
+
+    fn authors_display(&self) -> String {
+        if self.authors.is_empty() {
+            "Unknown Author".to_string()
+        } else {
+            self.authors
+                .iter()
+                .map(|a| a.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    }
+
+    fn title_matches(&self, re: &Regex) -> bool {
+        self.title.as_deref().is_some_and(|t| re.is_match(t))
+    }
+
+    fn authors_match(&self, re: &Regex) -> bool {
+        self.authors
+            .iter()
+            .any(|a| re.is_match(&a.name) || a.file_as.as_deref().is_some_and(|f| re.is_match(f)))
+    }
+
+    fn subjects_match(&self, re: &Regex) -> bool {
+        self.subjects.iter().any(|s| re.is_match(s))
+    }
+}
+
+// parse the OPF <metadata> block: title, creators (honoring EPUB3
+// file-as refinements for sort names), subjects/genre, and series
+// (calibre:series or EPUB3 belongs-to-collection)
+fn get_metadata(epub: &mut ZipArchive<File>, content_file_name: &str) -> EpubResult<BookMetadata> {
+    let content_file = epub.by_name(content_file_name).map_err(EpubError::Zip)?;
+    let mut event_reader = EventReader::new(content_file);
+
+    if seek_start_element(&mut event_reader, "metadata").is_none() {
+        return Err(EpubError::Epub("Malformed Xml".to_string()));
+    }
+
+    let mut metadata = BookMetadata::default();
+    let mut file_as = HashMap::new();
+    let mut pending_authors = Vec::new();
+
+    loop {
+        let event = match event_reader.next() {
+            Ok(event) => event,
+            Err(_) => return Err(EpubError::Epub("Malformed Xml".to_string())),
+        };
+
+        if is_end_element(&event, "metadata") {
+            break;
+        } else if is_start_element(&event, "title").is_some() {
+            if let Some(text) = read_element_text(&mut event_reader, "title") {
+                if metadata.title.is_none() {
+                    metadata.title = Some(text);
+                }
+            }
+        } else if let Some(attrs) = is_start_element(&event, "creator") {
+            let id = get_attribute(&attrs, "id");
+            if let Some(text) = read_element_text(&mut event_reader, "creator") {
+                pending_authors.push((id, text));
+            }
+        } else if is_start_element(&event, "subject").is_some() {
+            if let Some(text) = read_element_text(&mut event_reader, "subject") {
+                metadata.subjects.push(text);
+            }
+        } else if let Some(attrs) = is_start_element(&event, "meta") {
+            let property = get_attribute(&attrs, "property");
+            if property.as_deref() == Some("file-as") {
+                if let Some(id) = get_attribute(&attrs, "refines") {
+                    if let Some(text) = read_element_text(&mut event_reader, "meta") {
+                        file_as.insert(id.trim_start_matches('#').to_string(), text);
+                    }
+                }
+            } else if property.as_deref() == Some("belongs-to-collection") {
+                if let Some(text) = read_element_text(&mut event_reader, "meta") {
+                    if metadata.series.is_none() {
+                        metadata.series = Some(text);
+                    }
+                }
+            } else if get_attribute(&attrs, "name").as_deref() == Some("calibre:series") {
+                if let Some(content) = get_attribute(&attrs, "content") {
+                    if metadata.series.is_none() {
+                        metadata.series = Some(content);
+                    }
+                }
+            }
+        }
+    }
+
+    metadata.authors = pending_authors
+        .into_iter()
+        .map(|(id, name): (Option<String>, String)| Author {
+            file_as: id.and_then(|id| file_as.get(&id).cloned()),
+            name,
+        })
+        .collect();
+
+    Ok(metadata)
 }
 
 #[derive(Debug)]
@@ -226,12 +426,7 @@ fn parse_toc(toc: ZipFile, oebps: &str) -> Option<NavMap> {
     let mut event_reader = EventReader::new(toc);
 
     // loop until the start of the navmap
-    while event_reader
-        .next()
-        .ok()
-        .and_then(|e| is_start_element(&e, "navMap"))
-        .is_none()
-    {}
+    seek_start_element(&mut event_reader, "navMap")?;
 
     let mut points = Vec::new();
 
@@ -300,15 +495,162 @@ fn parse_nav_point(event_reader: &mut EventReader<ZipFile>, oebps: &str) -> Opti
     })
 }
 
-// iterator over the text in the paragraph of an xhtml file
+// parse either an NCX navMap or an EPUB3 nav document into the same
+// NavMap tree, depending on the format found in get_spine_documents
+fn parse_toc_file(toc: ZipFile, oebps: &str, format: TocFormat) -> Option<NavMap> {
+    match format {
+        TocFormat::Ncx => parse_toc(toc, oebps),
+        TocFormat::Nav => parse_nav_document(toc, oebps),
+    }
+}
+
+// find the <nav epub:type="toc"> element in an EPUB3 navigation
+// document and build a NavMap from its nested <ol>/<li>/<a> structure
+fn parse_nav_document(nav: ZipFile, oebps: &str) -> Option<NavMap> {
+    let mut event_reader = EventReader::new(nav);
+
+    loop {
+        let event = match event_reader.next() {
+            Ok(event) => event,
+            Err(_) => return None,
+        };
+
+        if let XmlEvent::EndDocument = event {
+            return None;
+        } else if let Some(attrs) = is_start_element(&event, "nav") {
+            if get_attribute(&attrs, "type").as_deref() == Some("toc") {
+                break;
+            }
+        }
+    }
+
+    // loop until the start of the <ol> belonging to this nav
+    seek_start_element(&mut event_reader, "ol")?;
+
+    let points = parse_nav_list(&mut event_reader, oebps)?;
+
+    Some(NavMap { points })
+}
+
+// when this is called, event_reader has already seen the start of the <ol>
+fn parse_nav_list(event_reader: &mut EventReader<ZipFile>, oebps: &str) -> Option<Vec<NavPoint>> {
+    let mut points = Vec::new();
+
+    loop {
+        let event = match event_reader.next() {
+            Ok(event) => event,
+            Err(_) => return None,
+        };
+
+        if is_end_element(&event, "ol") {
+            break;
+        } else if is_start_element(&event, "li").is_some() {
+            match parse_nav_li(event_reader, oebps) {
+                Some(point) => points.push(point),
+                None => return None,
+            }
+        }
+    }
+
+    Some(points)
+}
+
+// when this is called, event_reader has already seen the start of the <li>
+fn parse_nav_li(event_reader: &mut EventReader<ZipFile>, oebps: &str) -> Option<NavPoint> {
+    let mut label = String::new();
+    let mut content_src = String::new();
+    let mut points = Vec::new();
+
+    loop {
+        let event = match event_reader.next() {
+            Ok(event) => event,
+            Err(_) => return None,
+        };
+
+        if is_end_element(&event, "li") {
+            break;
+        } else if let Some(attrs) = is_start_element(&event, "a") {
+            if let Some(href) = get_attribute(&attrs, "href") {
+                content_src = format!("{}{}", oebps, href);
+            }
+            loop {
+                let e = match event_reader.next() {
+                    Ok(e) => e,
+                    Err(_) => return None,
+                };
+
+                if is_end_element(&e, "a") {
+                    break;
+                } else if let XmlEvent::Characters(s) = e {
+                    label.push_str(&s);
+                }
+            }
+        } else if is_start_element(&event, "ol").is_some() {
+            match parse_nav_list(event_reader, oebps) {
+                Some(ps) => points = ps,
+                None => return None,
+            }
+        }
+    }
+
+    Some(NavPoint {
+        label,
+        content_src,
+        points,
+    })
+}
+
+// tags whose closing marks the end of a logical block of text; inline
+// tags like span/em/a are not listed here, so their character data keeps
+// accumulating into the surrounding block instead of being split off
+fn is_block_element(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "li"
+            | "blockquote"
+            | "div"
+            | "td"
+            | "th"
+            | "tr"
+            | "dd"
+            | "dt"
+            | "pre"
+            | "caption"
+            | "figcaption"
+    )
+}
+
+// iterator over the readable text of an xhtml file, one String per
+// logical block (paragraph, heading, list item, table cell, ...)
 struct XhtmlTextIterator<'a> {
     event_reader: EventReader<ZipFile<'a>>,
+    in_body: bool,
+    current: String,
 }
 
 impl<'a> XhtmlTextIterator<'a> {
     fn new(file: ZipFile<'a>) -> Self {
         XhtmlTextIterator {
             event_reader: EventReader::new(file),
+            in_body: false,
+            current: String::new(),
+        }
+    }
+
+    // yield whatever text is still buffered, e.g. trailing text sitting
+    // directly in <body> after the last block element closed
+    fn flush(&mut self) -> Option<String> {
+        if self.current.trim().is_empty() {
+            self.current.clear();
+            None
+        } else {
+            Some(std::mem::take(&mut self.current))
         }
     }
 }
@@ -320,31 +662,30 @@ impl<'a> Iterator for XhtmlTextIterator<'a> {
         loop {
             let event = match self.event_reader.next() {
                 Ok(event) => event,
-                Err(_) => return None,
+                Err(_) => return self.flush(),
             };
 
-            if is_start_element(&event, "p").is_some() {
-                break;
-            } else if let XmlEvent::EndDocument = event {
-                return None;
+            if let XmlEvent::EndDocument = event {
+                return self.flush();
             }
-        }
 
-        let mut text = String::new();
-        loop {
-            let event = match self.event_reader.next() {
-                Ok(event) => event,
-                Err(_) => return None,
-            };
+            if !self.in_body {
+                if is_start_element(&event, "body").is_some() {
+                    self.in_body = true;
+                }
+                continue;
+            }
 
-            if is_end_element(&event, "p") {
-                break;
-            } else if let XmlEvent::Characters(s) = event {
-                text += &s;
+            match &event {
+                XmlEvent::Characters(s) => self.current.push_str(s),
+                XmlEvent::EndElement { name, .. } if is_block_element(&name.local_name) => {
+                    if let Some(text) = self.flush() {
+                        return Some(text);
+                    }
+                }
+                _ => {}
             }
         }
-
-        Some(text)
     }
 }
 
@@ -372,6 +713,170 @@ fn print_paragraph(stdout: &mut StandardStream, paragraph: &str, matches: Matche
     num_matches
 }
 
+// compile an optional filter pattern into a Regex, exiting the process
+// the same way an invalid main search regex does
+fn compile_filter(pattern: &Option<String>, stderr: &mut StandardStream) -> Option<Regex> {
+    pattern.as_deref().map(|p| match Regex::new(p) {
+        Ok(re) => re,
+        Err(_) => {
+            print_error(stderr, "invalid regular expression".to_string());
+            std::process::exit(1)
+        }
+    })
+}
+
+// peek at a file's leading bytes for the zip local-file-header magic,
+// since a library of books can contain .epub payloads under odd
+// suffixes (or no suffix at all)
+fn looks_like_zip(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    match File::open(path).and_then(|mut f| f.read_exact(&mut magic)) {
+        Ok(()) => magic == *b"PK\x03\x04",
+        Err(_) => false,
+    }
+}
+
+// an epub is a zip archive containing META-INF/container.xml; check for
+// both rather than trusting the file extension
+fn is_epub_file(path: &Path) -> bool {
+    if !looks_like_zip(path) {
+        return false;
+    }
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    match ZipArchive::new(file) {
+        Ok(mut archive) => archive.by_name("META-INF/container.xml").is_ok(),
+        Err(_) => false,
+    }
+}
+
+// recursively walk path, collecting the paths of files that look like
+// epubs into out; a file that looks like a zip archive but isn't an
+// epub gets one warning and is otherwise skipped, and the traversal
+// continues rather than aborting
+fn collect_books(path: &Path, stderr: &mut StandardStream, out: &mut Vec<String>) {
+    let mut visited_dirs = HashSet::new();
+    collect_books_in(path, stderr, out, &mut visited_dirs);
+}
+
+// walks path like collect_books, but also tracks the canonical path of
+// every directory entered, so a symlink cycle (common in cloud-synced
+// library folders) gets skipped with a warning instead of recursing
+// until the stack overflows
+fn collect_books_in(
+    path: &Path,
+    stderr: &mut StandardStream,
+    out: &mut Vec<String>,
+    visited_dirs: &mut HashSet<PathBuf>,
+) {
+    if path.is_dir() {
+        let canonical = match path.canonicalize() {
+            Ok(canonical) => canonical,
+            Err(_) => {
+                print_error(stderr, format!("unable to read directory {}", path.display()));
+                return;
+            }
+        };
+        if !visited_dirs.insert(canonical) {
+            print_error(
+                stderr,
+                format!("{} forms a symlink cycle, skipping", path.display()),
+            );
+            return;
+        }
+
+        let entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(_) => {
+                print_error(stderr, format!("unable to read directory {}", path.display()));
+                return;
+            }
+        };
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            collect_books_in(&entry.path(), stderr, out, visited_dirs);
+        }
+    } else if is_epub_file(path) {
+        if let Some(path) = path.to_str() {
+            out.push(path.to_string());
+        }
+    } else if looks_like_zip(path) {
+        print_error(stderr, format!("{} may not be an epub file", path.display()));
+    }
+}
+
+// open an epub, read its metadata and table of contents, and apply the
+// --author/--title/--subject filters, returning None for any file that
+// should be skipped; a file that can't be read as an epub gets an error
+// printed, while a book rejected by the filters is skipped silently
+fn open_book(
+    file_name: &str,
+    author_re: Option<&Regex>,
+    title_re: Option<&Regex>,
+    subject_re: Option<&Regex>,
+    stderr: &mut StandardStream,
+) -> Option<(ZipArchive<File>, BookMetadata, NavMap, Vec<String>)> {
+    let mut archive = match File::open(file_name)
+        .map_err(EpubError::IO)
+        .and_then(|f| ZipArchive::new(f).map_err(EpubError::Zip))
+    {
+        Ok(archive) => archive,
+        Err(e) => {
+            print_error(
+                stderr,
+                match e {
+                    EpubError::IO(_) => format!("unable to open {}", file_name),
+                    EpubError::Zip(_) => format!("{} may not be a zip archive", file_name),
+                    _ => "you shouldn't see this message".to_string(),
+                },
+            );
+            return None;
+        }
+    };
+
+    let metadata = get_content_file_name(&mut archive)
+        .and_then(|content_file_name| get_metadata(&mut archive, &content_file_name))
+        .unwrap_or_default();
+
+    if author_re.is_some_and(|re| !metadata.authors_match(re))
+        || title_re.is_some_and(|re| !metadata.title_matches(re))
+        || subject_re.is_some_and(|re| !metadata.subjects_match(re))
+    {
+        return None;
+    }
+
+    let (toc, toc_format, spine) = match get_spine_documents(&mut archive) {
+        Ok(t) => t,
+        Err(_) => {
+            print_error(
+                stderr,
+                format!("{} may not be an epub file", file_name),
+            );
+            return None;
+        }
+    };
+
+    let oebps = containing_folder(&toc);
+    let toc = match archive
+        .by_name(&toc)
+        .ok()
+        .and_then(|t| parse_toc_file(t, &oebps, toc_format))
+    {
+        Some(toc) => toc,
+        None => {
+            print_error(
+                stderr,
+                format!("{} has an unreadable table of contents", file_name),
+            );
+            return None;
+        }
+    };
+
+    Some((archive, metadata, toc, spine))
+}
+
 fn print_error(stderr: &mut StandardStream, message: String) {
     stderr
         .set_color(ColorSpec::new().set_fg(Some(Color::Red)))
@@ -381,6 +886,369 @@ fn print_error(stderr: &mut StandardStream, message: String) {
     writeln!(stderr, ": {}", message).unwrap();
 }
 
+// the full rendered text of one spine document, kept around so the
+// pager can scroll above and below a match without re-parsing the epub
+struct ChapterText {
+    label: String,
+    paragraphs: Vec<String>,
+}
+
+// a book opened for interactive browsing: its metadata and every
+// chapter's rendered text, in spine order
+struct BookData {
+    metadata: BookMetadata,
+    chapters: Vec<ChapterText>,
+}
+
+// the location of one regex match, as indices into the books/chapters
+// gathered for interactive mode
+struct Hit {
+    book: usize,
+    chapter: usize,
+    paragraph: usize,
+}
+
+// open every book and buffer its full text, recording the location of
+// each match as we go, so the pager can navigate by spine order instead
+// of re-parsing the epub for every keypress
+fn gather_books(
+    file_names: &[String],
+    re: &Regex,
+    author_re: Option<&Regex>,
+    title_re: Option<&Regex>,
+    subject_re: Option<&Regex>,
+    stderr: &mut StandardStream,
+) -> (Vec<BookData>, Vec<Hit>) {
+    let mut books = Vec::new();
+    let mut hits = Vec::new();
+
+    for file_name in file_names {
+        let (mut archive, metadata, toc, spine) =
+            match open_book(file_name, author_re, title_re, subject_re, stderr) {
+                Some(book) => book,
+                None => continue,
+            };
+
+        let mut chapters = Vec::new();
+        let mut chapter_label = String::new();
+        for doc in spine {
+            if let Some(c) = toc.describe(&doc) {
+                chapter_label = c;
+            }
+            let file = match archive.by_name(&doc) {
+                Ok(file) => file,
+                Err(_) => {
+                    print_error(stderr, format!("{} is a malformed epub", doc));
+                    continue;
+                }
+            };
+
+            let paragraphs: Vec<String> = XhtmlTextIterator::new(file).collect();
+            if paragraphs.is_empty() {
+                continue;
+            }
+
+            let chapter = chapters.len();
+            for (paragraph, text) in paragraphs.iter().enumerate() {
+                if re.is_match(text) {
+                    hits.push(Hit {
+                        book: books.len(),
+                        chapter,
+                        paragraph,
+                    });
+                }
+            }
+
+            chapters.push(ChapterText {
+                label: chapter_label.clone(),
+                paragraphs,
+            });
+        }
+
+        books.push(BookData { metadata, chapters });
+    }
+
+    (books, hits)
+}
+
+// how many paragraphs of context to scroll up when jumping to a match
+const PAGER_CONTEXT: usize = 2;
+
+fn scroll_offset_for_hit(paragraph: usize) -> usize {
+    paragraph.saturating_sub(PAGER_CONTEXT)
+}
+
+// which match, out of how many total, the pager is currently showing
+struct HitPosition {
+    index: usize,
+    total: usize,
+}
+
+// a word's byte range in the raw paragraph text, paired with its byte
+// range in the whitespace-collapsed text built from it
+struct WordOffsets {
+    raw: (usize, usize),
+    collapsed: (usize, usize),
+}
+
+// collapse runs of whitespace (including the embedded newlines XHTML
+// source tends to have) down to single spaces, so a paragraph is always
+// one logical line for the pager to wrap; returns the collapsed text
+// along with each word's offsets, so a match found against the raw
+// paragraph can be relocated into the collapsed/wrapped text the pager
+// actually displays
+fn collapse_paragraph(text: &str) -> (String, Vec<WordOffsets>) {
+    let mut collapsed = String::new();
+    let mut words = Vec::new();
+    let mut word_start = None;
+
+    for (i, c) in text.char_indices() {
+        if c.is_whitespace() {
+            if let Some(start) = word_start.take() {
+                push_word(&mut collapsed, &mut words, text, start, i);
+            }
+        } else if word_start.is_none() {
+            word_start = Some(i);
+        }
+    }
+    if let Some(start) = word_start {
+        push_word(&mut collapsed, &mut words, text, start, text.len());
+    }
+
+    (collapsed, words)
+}
+
+fn push_word(
+    collapsed: &mut String,
+    words: &mut Vec<WordOffsets>,
+    text: &str,
+    raw_start: usize,
+    raw_end: usize,
+) {
+    if !collapsed.is_empty() {
+        collapsed.push(' ');
+    }
+    let collapsed_start = collapsed.len();
+    collapsed.push_str(&text[raw_start..raw_end]);
+    words.push(WordOffsets {
+        raw: (raw_start, raw_end),
+        collapsed: (collapsed_start, collapsed.len()),
+    });
+}
+
+// relocate a byte offset from the raw paragraph text into the
+// corresponding offset in its whitespace-collapsed text
+fn map_to_collapsed(offset: usize, words: &[WordOffsets], collapsed_len: usize) -> usize {
+    for word in words {
+        let (raw_start, raw_end) = word.raw;
+        let (collapsed_start, collapsed_end) = word.collapsed;
+
+        if offset <= raw_start {
+            return collapsed_start;
+        }
+        if offset <= raw_end {
+            return collapsed_start + (offset - raw_start).min(collapsed_end - collapsed_start);
+        }
+    }
+    collapsed_len
+}
+
+// greedily pack words into lines no wider than `width` columns, returning
+// the (start, end) byte range of the collapsed text covered by each line;
+// a single word longer than `width` is not split and gets a line of its
+// own that is wider than `width`
+fn wrap_lines(words: &[WordOffsets], collapsed: &str, width: usize) -> Vec<(usize, usize)> {
+    let width = width.max(1);
+    if words.is_empty() {
+        return vec![(0, 0)];
+    }
+
+    let mut lines = Vec::new();
+    let mut line_start = 0;
+    let mut col = 0usize;
+
+    for (i, word) in words.iter().enumerate() {
+        let (word_start, word_end) = word.collapsed;
+        let word_len = collapsed[word_start..word_end].chars().count();
+        let needed = word_len + if i > line_start { 1 } else { 0 };
+
+        if i > line_start && col + needed > width {
+            lines.push((words[line_start].collapsed.0, words[i - 1].collapsed.1));
+            line_start = i;
+            col = word_len;
+        } else {
+            col += needed;
+        }
+    }
+    lines.push((
+        words[line_start].collapsed.0,
+        words[words.len() - 1].collapsed.1,
+    ));
+
+    lines
+}
+
+// draw the current chapter, starting at scroll_offset, with the match
+// at hit_paragraph highlighted; paragraphs are wrapped to the terminal
+// width at word boundaries so a long or multiline paragraph doesn't bleed
+// into the next row (a single word wider than the terminal still does)
+fn render_screen(
+    stdout: &mut std::io::Stdout,
+    header: &str,
+    chapter: &ChapterText,
+    scroll_offset: usize,
+    hit_paragraph: usize,
+    re: &Regex,
+    position: &HitPosition,
+) -> std::io::Result<()> {
+    let (cols, rows) = terminal_size()?;
+    let body_rows = rows.saturating_sub(2);
+    let width = cols as usize;
+
+    execute!(stdout, Clear(ClearType::All), MoveTo(0, 0))?;
+    execute!(
+        stdout,
+        SetForegroundColor(CtColor::Green),
+        Print(header),
+        ResetColor,
+        Print(format!("  [{}/{}]", position.index + 1, position.total)),
+    )?;
+
+    let mut row = 1;
+    'paragraphs: for (idx, paragraph) in chapter.paragraphs.iter().enumerate().skip(scroll_offset) {
+        let (collapsed, words) = collapse_paragraph(paragraph);
+        // match against the same raw paragraph text gather_books used to
+        // find this hit, then relocate the match into the collapsed text,
+        // since a pattern sensitive to literal whitespace (e.g. `\s{2,}`)
+        // can match the raw text without matching its collapsed form
+        let matches: Vec<(usize, usize)> = if idx == hit_paragraph {
+            re.find_iter(paragraph)
+                .map(|m| {
+                    (
+                        map_to_collapsed(m.start(), &words, collapsed.len()),
+                        map_to_collapsed(m.end(), &words, collapsed.len()),
+                    )
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        for (line_start, line_end) in wrap_lines(&words, &collapsed, width) {
+            if row >= body_rows {
+                break 'paragraphs;
+            }
+            execute!(stdout, MoveTo(0, row))?;
+
+            let mut previous_end = line_start;
+            for &(match_start, match_end) in &matches {
+                let start = match_start.max(line_start);
+                let end = match_end.min(line_end);
+                if start >= end {
+                    continue;
+                }
+                execute!(stdout, Print(&collapsed[previous_end..start]))?;
+                execute!(
+                    stdout,
+                    SetForegroundColor(CtColor::Blue),
+                    Print(&collapsed[start..end]),
+                    ResetColor,
+                )?;
+                previous_end = end;
+            }
+            execute!(stdout, Print(&collapsed[previous_end..line_end]))?;
+
+            row += 1;
+        }
+    }
+
+    execute!(
+        stdout,
+        MoveTo(0, rows.saturating_sub(1)),
+        SetForegroundColor(CtColor::DarkGrey),
+        Print("n/p: next/prev match   j/k: scroll   q: quit"),
+        ResetColor,
+    )?;
+
+    use std::io::Write as _;
+    stdout.flush()
+}
+
+// an alternate-screen pager over the gathered matches; the caller is
+// responsible for checking that stdout is a tty before calling this
+fn run_pager(books: &[BookData], hits: &[Hit], re: &Regex) -> std::io::Result<()> {
+    if hits.is_empty() {
+        return Ok(());
+    }
+
+    let mut stdout = std::io::stdout();
+    enable_raw_mode()?;
+    execute!(stdout, EnterAlternateScreen)?;
+
+    let mut current = 0;
+    let mut scroll_offset = {
+        let hit = &hits[current];
+        scroll_offset_for_hit(hit.paragraph)
+    };
+
+    let result = (|| -> std::io::Result<()> {
+        loop {
+            let hit = &hits[current];
+            let book = &books[hit.book];
+            let chapter = &book.chapters[hit.chapter];
+            let header = format!(
+                "{} by {} ({})",
+                book.metadata.title_or_unknown(),
+                book.metadata.authors_display(),
+                chapter.label
+            );
+
+            render_screen(
+                &mut stdout,
+                &header,
+                chapter,
+                scroll_offset,
+                hit.paragraph,
+                re,
+                &HitPosition {
+                    index: current,
+                    total: hits.len(),
+                },
+            )?;
+
+            if let Event::Key(key) = read_event()? {
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => break,
+                    KeyCode::Char('n') | KeyCode::Right | KeyCode::Down
+                        if current + 1 < hits.len() =>
+                    {
+                        current += 1;
+                        scroll_offset = scroll_offset_for_hit(hits[current].paragraph);
+                    }
+                    KeyCode::Char('p') | KeyCode::Left | KeyCode::Up if current > 0 => {
+                        current -= 1;
+                        scroll_offset = scroll_offset_for_hit(hits[current].paragraph);
+                    }
+                    KeyCode::Char('j') | KeyCode::PageDown => {
+                        let max = chapter.paragraphs.len().saturating_sub(1);
+                        scroll_offset = (scroll_offset + 1).min(max);
+                    }
+                    KeyCode::Char('k') | KeyCode::PageUp => {
+                        scroll_offset = scroll_offset.saturating_sub(1);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    })();
+
+    execute!(stdout, LeaveAlternateScreen)?;
+    disable_raw_mode()?;
+
+    result
+}
+
 #[derive(FromArgs, Debug)]
 /// Search an epub for a regular expression
 struct EpubArgs {
@@ -400,17 +1268,39 @@ struct EpubArgs {
     /// find matches surrounded by word boundaries
     word_regexp: bool,
 
+    #[argh(switch, short = 'r')]
+    /// recursively search a directory of epub files, selecting them by
+    /// content rather than file extension
+    recursive: bool,
+
+    #[argh(switch, short = 'I')]
+    /// page through matches one chapter at a time instead of printing
+    /// them all; falls back to normal output when stdout is not a tty
+    interactive: bool,
+
     #[argh(option, default = "String::from(\"auto\")")]
     /// whether to print results in color.
     /// options: always, auto, never
     color: String,
 
+    #[argh(option)]
+    /// only search books whose author matches this regex
+    author: Option<String>,
+
+    #[argh(option)]
+    /// only search books whose title matches this regex
+    title: Option<String>,
+
+    #[argh(option)]
+    /// only search books whose subject/genre matches this regex
+    subject: Option<String>,
+
     #[argh(positional)]
     /// regular Expression
     regex: String,
 
     #[argh(positional)]
-    /// files to search
+    /// files to search, or directories to search with --recursive
     file_names: Vec<String>,
 }
 
@@ -457,50 +1347,48 @@ fn main() {
         }
     };
 
-    let mut found_match = false;
+    let author_re = compile_filter(&args.author, &mut stderr);
+    let title_re = compile_filter(&args.title, &mut stderr);
+    let subject_re = compile_filter(&args.subject, &mut stderr);
 
-    for file_name in args.file_names {
-        // open up the file as a zip archive
-        let mut archive = match File::open(file_name.clone())
-            .map_err(EpubError::IO)
-            .and_then(|f| ZipArchive::new(f).map_err(EpubError::Zip))
-        {
-            Ok(archive) => archive,
-            Err(e) => {
-                print_error(
-                    &mut stderr,
-                    match e {
-                        EpubError::IO(_) => format!("unable to open {}", file_name),
-                        EpubError::Zip(_) => format!("{} may not be a zip archive", file_name),
-                        _ => "you shouldn't see this message".to_string(),
-                    },
-                );
-                continue;
-            }
-        };
+    let file_names = if args.recursive {
+        let mut collected = Vec::new();
+        for name in &args.file_names {
+            collect_books(Path::new(name), &mut stderr, &mut collected);
+        }
+        collected
+    } else {
+        args.file_names
+    };
 
-        let (toc, spine) = match get_spine_documents(&mut archive) {
-            Ok(t) => t,
-            Err(_) => {
-                print_error(
-                    &mut stderr,
-                    format!("{} may not be an epub file", file_name),
-                );
-                continue;
-            }
-        };
+    if args.interactive && atty::is(atty::Stream::Stdout) {
+        let (books, hits) = gather_books(
+            &file_names,
+            &re,
+            author_re.as_ref(),
+            title_re.as_ref(),
+            subject_re.as_ref(),
+            &mut stderr,
+        );
+        if run_pager(&books, &hits, &re).is_err() {
+            print_error(&mut stderr, "interactive pager failed".to_string());
+            std::process::exit(1)
+        }
+        std::process::exit(if hits.is_empty() { 1 } else { 0 });
+    }
 
-        let oebps = containing_folder(&toc);
-        let toc = match archive
-            .by_name(&toc)
-            .ok()
-            .and_then(|t| parse_toc(t, &oebps))
-        {
-            Some(toc) => toc,
-            None => {
-                print_error(&mut stderr, "Error reading table of contents".to_string());
-                std::process::exit(0);
-            }
+    let mut found_match = false;
+
+    for file_name in file_names {
+        let (mut archive, metadata, toc, spine) = match open_book(
+            &file_name,
+            author_re.as_ref(),
+            title_re.as_ref(),
+            subject_re.as_ref(),
+            &mut stderr,
+        ) {
+            Some(book) => book,
+            None => continue,
         };
 
         let mut num_matches = 0;
@@ -527,7 +1415,14 @@ fn main() {
                         stdout
                             .set_color(ColorSpec::new().set_fg(Some(Color::Green)))
                             .unwrap();
-                        write!(&mut stdout, "{}({})", file_name, chapter).unwrap();
+                        write!(
+                            &mut stdout,
+                            "{} by {} ({})",
+                            metadata.title_or_unknown(),
+                            metadata.authors_display(),
+                            chapter
+                        )
+                        .unwrap();
                         stdout.set_color(ColorSpec::new().set_fg(None)).unwrap();
                         write!(&mut stdout, ": ").unwrap();
                         num_matches += print_paragraph(&mut stdout, &paragraph, matches);